@@ -6,24 +6,27 @@ extern crate futures;
 extern crate log;
 extern crate log4rs;
 extern crate rusoto_core as aws;
+extern crate rusoto_credential;
 extern crate rusoto_s3 as s3;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate mime_guess;
 extern crate num_cpus;
+extern crate tokio_timer;
 extern crate toml;
 
 use std::path::Path;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use actix_web::{
-    http::Method, http::{ContentEncoding, StatusCode}, server, App, Body, HttpMessage, HttpRequest,
-    HttpResponse, Path as UrlPath,
+    http::Method, http::{header, ContentEncoding, StatusCode}, middleware, server, App, Body,
+    HttpMessage, HttpRequest, HttpResponse, Path as UrlPath,
 };
 use failure::Error;
 use futures::{
-    future::{self, Either}, Future, Stream,
+    future::{self, Either}, Async, Future, Stream,
 };
 
 trait OptionExt<T> {
@@ -57,10 +60,133 @@ struct Config {
     /// Which AWS region to use.
     pub region: String,
 
+    /// Custom S3-compatible endpoint to talk to instead of AWS, e.g.
+    /// `http://localhost:9000` for a local MinIO instance. rusoto addresses
+    /// a `Region::Custom` endpoint as `endpoint/bucket/key` (path-style)
+    /// unconditionally — it has no virtual-hosted-style option for
+    /// non-AWS regions — so there's no separate setting for this.
+    pub endpoint: Option<String>,
+
+    /// Access key to use when `endpoint` is set. Ignored otherwise, in which
+    /// case rusoto falls back to its normal credential chain.
+    pub access_key: Option<String>,
+
+    /// Secret key to use when `endpoint` is set.
+    pub secret_key: Option<String>,
+
+    /// Render an HTML directory listing for keys that are empty or end in
+    /// `/`, instead of returning 404.
+    #[serde(default)]
+    pub enable_index: bool,
+
+    /// If set, bounds how long an S3 request may go without making
+    /// progress: the initial response (or HEAD) has this many seconds to
+    /// arrive, and once a response starts streaming, the deadline resets on
+    /// every chunk received, so the timeout fires on a stalled transfer but
+    /// never trips on a slow-but-steady one no matter how long it runs.
+    pub request_timeout_secs: Option<u64>,
+
+    /// Objects larger than this (in bytes) are fetched as several concurrent
+    /// ranged GETs instead of one streaming request. `None` disables
+    /// parallel fetching and always streams the object as a whole.
+    pub parallel_fetch_threshold: Option<u64>,
+
+    /// Size in bytes of each range fetched in parallel mode.
+    #[serde(default = "default_parallel_chunk_size")]
+    pub parallel_chunk_size: u64,
+
+    /// Maximum number of ranges fetched concurrently.
+    #[serde(default = "default_max_parallel_chunks")]
+    pub max_parallel_chunks: usize,
+
+    /// CORS settings. When absent, no `Access-Control-*` headers are sent
+    /// and `OPTIONS` requests get a plain 404.
+    pub cors: Option<CorsConfig>,
+
     /// Number of actix workers
     pub workers: Option<usize>,
 }
 
+fn default_parallel_chunk_size() -> u64 {
+    8 * 1024 * 1024
+}
+
+fn default_max_parallel_chunks() -> usize {
+    4
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CorsConfig {
+    /// Origins allowed to fetch proxied objects, or `["*"]` for any origin.
+    pub allowed_origins: Vec<String>,
+
+    /// Methods advertised in the preflight `Access-Control-Allow-Methods`
+    /// response.
+    #[serde(default = "default_cors_methods")]
+    pub allowed_methods: Vec<String>,
+
+    /// Headers advertised in the preflight `Access-Control-Allow-Headers`
+    /// response.
+    #[serde(default)]
+    pub allowed_headers: Vec<String>,
+
+    /// How long, in seconds, a browser may cache a preflight response.
+    #[serde(default = "default_cors_max_age")]
+    pub max_age: u64,
+}
+
+fn default_cors_methods() -> Vec<String> {
+    vec!["GET".to_string(), "HEAD".to_string()]
+}
+
+fn default_cors_max_age() -> u64 {
+    3600
+}
+
+/// Returns the value to send back as `Access-Control-Allow-Origin` if
+/// `origin` is allowed by `cors`, honoring a literal `*` wildcard entry.
+fn matched_origin<'a>(cors: &'a CorsConfig, origin: &str) -> Option<&'a str> {
+    if cors.allowed_origins.iter().any(|allowed| allowed == "*") {
+        Some("*")
+    } else {
+        cors.allowed_origins
+            .iter()
+            .find(|allowed| allowed.as_str() == origin)
+            .map(String::as_str)
+    }
+}
+
+/// Builds the S3 client according to the configuration. When `endpoint` is
+/// set the client talks to a custom, S3-compatible region using static
+/// credentials instead of AWS with the default credential provider chain.
+fn build_s3_client(config: &Config) -> Result<s3::S3Client> {
+    use aws::{HttpClient, Region};
+    use rusoto_credential::StaticProvider;
+
+    if let Some(ref endpoint) = config.endpoint {
+        let region = Region::Custom {
+            name: config.region.clone(),
+            endpoint: endpoint.clone(),
+        };
+        let credentials = StaticProvider::new(
+            config.access_key.clone().unwrap_or_default(),
+            config.secret_key.clone().unwrap_or_default(),
+            None,
+            None,
+        );
+
+        info!("Using custom S3 endpoint '{}'", endpoint);
+        Ok(s3::S3Client::new_with(
+            HttpClient::new()?,
+            credentials,
+            region,
+        ))
+    } else {
+        let region = config.region.parse()?;
+        Ok(s3::S3Client::new(region))
+    }
+}
+
 fn read_config() -> Result<Config> {
     const CONFIG_FILE: &str = "s3-proxy.toml";
 
@@ -87,7 +213,79 @@ struct State {
     config: Config,
 }
 
-fn handle_response(res: s3::GetObjectOutput, key: String) -> HttpResponse {
+/// Target size for each part of a multipart upload. Uploads that fit in a
+/// single part this size or smaller are sent as a plain `PutObjectRequest`
+/// instead of going through the multipart upload flow.
+const MULTIPART_PART_SIZE: usize = 8 * 1024 * 1024;
+
+/// Resets a deadline every time an item comes through, so a connection that
+/// goes silent mid-transfer errors out instead of streaming forever, while a
+/// large-but-healthy transfer is never penalized just for taking a while.
+struct TimeoutStream<S> {
+    inner: S,
+    delay: tokio_timer::Delay,
+    duration: Duration,
+}
+
+impl<S> TimeoutStream<S> {
+    fn new(inner: S, duration: Duration) -> Self {
+        TimeoutStream {
+            inner,
+            delay: tokio_timer::Delay::new(Instant::now() + duration),
+            duration,
+        }
+    }
+}
+
+impl<S: Stream<Error = Error>> Stream for TimeoutStream<S> {
+    type Item = S::Item;
+    type Error = Error;
+
+    fn poll(&mut self) -> futures::Poll<Option<Self::Item>, Error> {
+        match self.inner.poll()? {
+            Async::Ready(item) => {
+                self.delay.reset(Instant::now() + self.duration);
+                Ok(Async::Ready(item))
+            }
+            Async::NotReady => match self.delay.poll() {
+                Ok(Async::Ready(())) => Err(failure::err_msg("S3 stream stalled")),
+                Ok(Async::NotReady) => Ok(Async::NotReady),
+                Err(e) => Err(Error::from(e)),
+            },
+        }
+    }
+}
+
+/// Wraps `fut` so it's dropped (cancelling whatever request it represents)
+/// if it doesn't resolve within `request_timeout_secs` seconds. `on_timeout`
+/// builds the error to report in that case; other errors pass through
+/// unchanged. A `None` timeout is a no-op passthrough.
+fn with_timeout<F>(
+    fut: F,
+    request_timeout_secs: Option<u64>,
+    on_timeout: impl FnOnce() -> F::Error + 'static,
+) -> Box<Future<Item = F::Item, Error = F::Error>>
+where
+    F: Future + 'static,
+{
+    match request_timeout_secs {
+        Some(secs) => Box::new(tokio_timer::Timeout::new(fut, Duration::from_secs(secs)).then(
+            move |result| match result {
+                Ok(item) => Ok(item),
+                Err(ref e) if e.is_elapsed() => Err(on_timeout()),
+                Err(e) => Err(e.into_inner()
+                    .expect("Timeout error that is neither elapsed nor inner")),
+            },
+        )),
+        None => Box::new(fut),
+    }
+}
+
+fn handle_response(
+    res: s3::GetObjectOutput,
+    key: String,
+    request_timeout_secs: Option<u64>,
+) -> HttpResponse {
     use bytes::Bytes;
     debug!("S3 response: {:?}", res);
 
@@ -95,6 +293,15 @@ fn handle_response(res: s3::GetObjectOutput, key: String) -> HttpResponse {
         .expect("No body for response")
         .map(Bytes::from)
         .map_err(Error::from);
+    // A client disconnect needs no extra plumbing: actix drops this
+    // `Body::Streaming` stream, which drops `body` (and the underlying hyper
+    // connection to S3) along with it. `request_timeout_secs`, on the other
+    // hand, needs an explicit deadline so a connection that stops producing
+    // bytes without ever closing doesn't hang around forever.
+    let body: Box<Stream<Item = Bytes, Error = Error>> = match request_timeout_secs {
+        Some(secs) => Box::new(TimeoutStream::new(body, Duration::from_secs(secs))),
+        None => Box::new(body),
+    };
     let mut builder = HttpResponse::Ok();
 
     if let Some(content_length) = res.content_length {
@@ -145,51 +352,926 @@ fn handle_response(res: s3::GetObjectOutput, key: String) -> HttpResponse {
     builder.body(Body::Streaming(Box::new(body.map_err(From::from))))
 }
 
+/// Builds the `304 Not Modified` response S3 returns when a conditional GET
+/// (`If-None-Match` / `If-Modified-Since`) matches the current object. No
+/// body is sent, only the headers a client needs to keep using its cache.
+fn not_modified_response(resp: &aws::request::BufferedHttpResponse) -> HttpResponse {
+    let mut builder = HttpResponse::build(StatusCode::NOT_MODIFIED);
+
+    for header in &["ETag", "Last-Modified"] {
+        if let Some(value) = resp.headers.get(*header) {
+            builder.header(*header, value.as_str());
+        }
+    }
+    builder.header("Cache-Control", "public, max-age=31536000");
+
+    builder.finish()
+}
+
 fn handler(
     (req, path): (HttpRequest<State>, UrlPath<String>),
 ) -> Box<Future<Item = HttpResponse, Error = Error>> {
-    use s3::S3;
-
     let client = Arc::clone(&req.state().s3_client);
     let config = &req.state().config;
-    let range = req.headers()
+    let range: Option<String> = req.headers()
         .get("Range")
         .and_then(|r| r.to_str().ok())
         .map(From::from);
+    let if_none_match = req.headers()
+        .get("If-None-Match")
+        .and_then(|r| r.to_str().ok())
+        .map(From::from);
+    let if_modified_since = req.headers()
+        .get("If-Modified-Since")
+        .and_then(|r| r.to_str().ok())
+        .map(From::from);
 
     let key = path.into_inner();
     let bucket = config.bucket.clone();
 
+    if config.enable_index && (key.is_empty() || key.ends_with('/')) {
+        return list_directory(client, bucket, key);
+    }
+
     if key.is_empty() {
         return Box::new(future::ok(HttpResponse::NotFound().body("404 - Not found")));
     }
 
     debug!("Request headers: {:?}", req.headers());
-    let resp = client
-        .get_object(s3::GetObjectRequest {
-            bucket,
-            key: key.clone(),
-            range,
-            ..Default::default()
+
+    // Plain, unconditional whole-object GETs are the only ones eligible for
+    // the parallel chunked fetch path; ranged/conditional requests always go
+    // through the regular single-request flow below.
+    if let Some(threshold) = config.parallel_fetch_threshold {
+        if range.is_none() && if_none_match.is_none() && if_modified_since.is_none() {
+            return accelerated_handler(
+                client,
+                bucket,
+                key,
+                threshold,
+                config.parallel_chunk_size,
+                config.max_parallel_chunks,
+                config.request_timeout_secs,
+            );
+        }
+    }
+
+    fetch_single_object(
+        client,
+        bucket,
+        key,
+        range,
+        if_none_match,
+        if_modified_since,
+        config.request_timeout_secs,
+    )
+}
+
+/// Fetches a single key with one `GetObjectRequest`, honoring an optional
+/// byte range, conditional headers, and per-request timeout.
+fn fetch_single_object(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    range: Option<String>,
+    if_none_match: Option<String>,
+    if_modified_since: Option<String>,
+    request_timeout_secs: Option<u64>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    use s3::S3;
+
+    let timeout_key = key.clone();
+
+    let get_object = client.get_object(s3::GetObjectRequest {
+        bucket,
+        key: key.clone(),
+        range,
+        if_none_match,
+        if_modified_since,
+        ..Default::default()
+    });
+
+    // Bounds time-to-first-byte; the body itself is bounded separately by
+    // wrapping the streaming response in a `TimeoutStream` once it's built.
+    let get_object = with_timeout(get_object, request_timeout_secs, move || {
+        debug!("S3 request for {} timed out", timeout_key);
+        s3::GetObjectError::Unknown(aws::request::BufferedHttpResponse {
+            status: StatusCode::GATEWAY_TIMEOUT,
+            body: Default::default(),
+            headers: Default::default(),
         })
-        .then(|result| {
-            if let Err(s3::GetObjectError::NoSuchKey(_)) = result {
+    });
+
+    let resp = get_object
+        .then(|result| match result {
+            Err(s3::GetObjectError::NoSuchKey(_)) => {
                 Ok(Either::B(HttpResponse::NotFound().body("404 - Not found")))
-            } else {
-                result.map(Either::A)
             }
+            Err(s3::GetObjectError::Unknown(ref resp))
+                if resp.status == StatusCode::NOT_MODIFIED =>
+            {
+                Ok(Either::B(not_modified_response(resp)))
+            }
+            Err(s3::GetObjectError::Unknown(ref resp))
+                if resp.status == StatusCode::GATEWAY_TIMEOUT =>
+            {
+                Ok(Either::B(
+                    HttpResponse::build(StatusCode::GATEWAY_TIMEOUT)
+                        .body("504 - S3 request timed out"),
+                ))
+            }
+            other => other.map(Either::A),
         })
         .from_err()
-        .map(|res| match res {
-            Either::A(res) => handle_response(res, key),
+        .map(move |res| match res {
+            Either::A(res) => handle_response(res, key, request_timeout_secs),
             Either::B(res) => res,
         });
 
     Box::new(resp)
 }
 
+/// Checks the object's size with a `HeadObjectRequest` and, if it's larger
+/// than `threshold`, fetches it as several concurrent ranged GETs instead of
+/// a single streaming request; otherwise falls back to [`fetch_single_object`].
+fn accelerated_handler(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    threshold: u64,
+    chunk_size: u64,
+    max_parallel_chunks: usize,
+    request_timeout_secs: Option<u64>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    use s3::S3;
+
+    enum HeadOutcome {
+        Ready(HttpResponse),
+        BelowThreshold,
+    }
+
+    let head_bucket = bucket.clone();
+    let head_key = key.clone();
+    let timeout_key = key.clone();
+    let fallback_client = Arc::clone(&client);
+    let fallback_bucket = bucket.clone();
+    let fallback_key = key.clone();
+
+    let head_object = client.head_object(s3::HeadObjectRequest {
+        bucket: head_bucket,
+        key: head_key,
+        ..Default::default()
+    });
+    let head_object = with_timeout(head_object, request_timeout_secs, move || {
+        debug!("S3 HEAD request for {} timed out", timeout_key);
+        s3::HeadObjectError::Unknown(aws::request::BufferedHttpResponse {
+            status: StatusCode::GATEWAY_TIMEOUT,
+            body: Default::default(),
+            headers: Default::default(),
+        })
+    });
+
+    let fut = head_object
+        .then(move |result| match result {
+            Err(s3::HeadObjectError::Unknown(ref resp)) if resp.status == StatusCode::NOT_FOUND => {
+                Ok(HeadOutcome::Ready(
+                    HttpResponse::NotFound().body("404 - Not found"),
+                ))
+            }
+            Err(s3::HeadObjectError::Unknown(ref resp)) if resp.status == StatusCode::GATEWAY_TIMEOUT => {
+                Ok(HeadOutcome::Ready(
+                    HttpResponse::build(StatusCode::GATEWAY_TIMEOUT)
+                        .body("504 - S3 request timed out"),
+                ))
+            }
+            Err(e) => Err(Error::from(e)),
+            Ok(head) => {
+                let content_length = head.content_length.unwrap_or(0).max(0) as u64;
+                if content_length > threshold {
+                    debug!(
+                        "Object {} is {} bytes, fetching with {} parallel chunks of {} bytes",
+                        key, content_length, max_parallel_chunks, chunk_size
+                    );
+                    Ok(HeadOutcome::Ready(parallel_chunked_response(
+                        Arc::clone(&client),
+                        bucket.clone(),
+                        key.clone(),
+                        head,
+                        content_length,
+                        chunk_size,
+                        max_parallel_chunks,
+                        request_timeout_secs,
+                    )))
+                } else {
+                    Ok(HeadOutcome::BelowThreshold)
+                }
+            }
+        })
+        .and_then(move |outcome| match outcome {
+            HeadOutcome::Ready(resp) => Either::A(future::ok(resp)),
+            HeadOutcome::BelowThreshold => Either::B(fetch_single_object(
+                fallback_client,
+                fallback_bucket,
+                fallback_key,
+                None,
+                None,
+                None,
+                request_timeout_secs,
+            )),
+        });
+
+    Box::new(fut)
+}
+
+/// Builds the response for a parallel chunked fetch: the headers come from
+/// the already-completed `HeadObjectOutput`, and the body concatenates each
+/// chunk's bytes in order as they become available.
+fn parallel_chunked_response(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    head: s3::HeadObjectOutput,
+    content_length: u64,
+    chunk_size: u64,
+    max_parallel_chunks: usize,
+    request_timeout_secs: Option<u64>,
+) -> HttpResponse {
+    use bytes::Bytes;
+
+    let mut builder = HttpResponse::Ok();
+    builder.content_length(content_length);
+    if let Some(content_type) = head.content_type {
+        // Don't gzip media files
+        if content_type.starts_with("audio")
+            || content_type.starts_with("video")
+            || content_type.starts_with("image")
+        {
+            debug!("not GZIPping media file");
+            builder.content_encoding(ContentEncoding::Identity);
+        }
+        if content_type == "binary/octet-stream" || content_type == "application/octet-stream" {
+            if let Some(extension) = Path::new(&key).extension().and_then(|s| s.to_str()) {
+                debug!("File has extension {}", extension);
+                let mime = mime_guess::get_mime_type(extension);
+                let mime = mime.as_ref();
+                debug!("Determined file type {} from extension", mime);
+                builder.content_type(mime);
+            }
+        } else {
+            builder.content_type(content_type.as_str());
+        }
+    }
+    if let Some(e_tag) = head.e_tag {
+        builder.header("ETag", e_tag);
+    }
+    if let Some(last_modified) = head.last_modified {
+        builder.header("Last-Modified", last_modified);
+    }
+    builder.header("Cache-Control", "public, max-age=31536000");
+
+    let body: Box<Stream<Item = Bytes, Error = Error>> = chunked_range_stream(
+        client,
+        bucket,
+        key,
+        content_length,
+        chunk_size,
+        max_parallel_chunks,
+        request_timeout_secs,
+    );
+
+    builder.body(Body::Streaming(Box::new(body.map_err(From::from))))
+}
+
+/// Splits `[0, content_length)` into fixed-size ranges and fetches them in
+/// groups of up to `max_parallel_chunks` concurrently, yielding each group's
+/// bytes in order before starting the next one. This bounds both the number
+/// of in-flight requests and the amount of out-of-order data buffered in
+/// memory at any one time.
+fn chunked_range_stream(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    content_length: u64,
+    chunk_size: u64,
+    max_parallel_chunks: usize,
+    request_timeout_secs: Option<u64>,
+) -> Box<Stream<Item = bytes::Bytes, Error = Error>> {
+    use futures::stream::FuturesOrdered;
+
+    let groups = chunk_ranges(content_length, chunk_size.max(1))
+        .chunks(max_parallel_chunks.max(1))
+        .map(|group| group.to_vec())
+        .collect::<Vec<_>>()
+        .into_iter();
+
+    // Each step fetches one group of ranges concurrently (bounding both
+    // in-flight requests and buffered memory to `max_parallel_chunks`
+    // chunks), then hands the group's bytes to the stream, in order, before
+    // the next group starts.
+    let body = futures::stream::unfold(groups, move |mut groups| {
+        groups.next().map(|group| {
+            let fetches: FuturesOrdered<_> = group
+                .into_iter()
+                .map(|(start, end)| {
+                    fetch_range(
+                        Arc::clone(&client),
+                        bucket.clone(),
+                        key.clone(),
+                        start,
+                        end,
+                        request_timeout_secs,
+                    )
+                })
+                .collect();
+
+            fetches
+                .collect()
+                .map(|chunks: Vec<bytes::Bytes>| (chunks, groups))
+        })
+    }).map(|chunks| futures::stream::iter_ok::<_, Error>(chunks))
+        .flatten();
+
+    Box::new(body)
+}
+
+/// Fetches a single byte range `[start, end]` (inclusive) and buffers it
+/// into one contiguous `Bytes` value.
+fn fetch_range(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    start: u64,
+    end: u64,
+    request_timeout_secs: Option<u64>,
+) -> Box<Future<Item = bytes::Bytes, Error = Error>> {
+    use bytes::{Bytes, BytesMut};
+    use s3::S3;
+
+    let range = format!("bytes={}-{}", start, end);
+    debug!("Fetching range {} of {}", range, key);
+
+    let timeout_key = key.clone();
+    let get_object = client.get_object(s3::GetObjectRequest {
+        bucket,
+        key,
+        range: Some(range),
+        ..Default::default()
+    });
+    let get_object = with_timeout(get_object, request_timeout_secs, move || {
+        debug!("S3 range request for {} timed out", timeout_key);
+        s3::GetObjectError::Unknown(aws::request::BufferedHttpResponse {
+            status: StatusCode::GATEWAY_TIMEOUT,
+            body: Default::default(),
+            headers: Default::default(),
+        })
+    });
+
+    let fut = get_object.from_err().and_then(move |res| {
+        let body = res.body
+            .expect("No body for response")
+            .map(Bytes::from)
+            .map_err(Error::from);
+        let body: Box<Stream<Item = Bytes, Error = Error>> = match request_timeout_secs {
+            Some(secs) => Box::new(TimeoutStream::new(body, Duration::from_secs(secs))),
+            None => Box::new(body),
+        };
+        body.fold(BytesMut::new(), |mut acc, chunk| {
+            acc.extend_from_slice(&chunk);
+            future::ok::<_, Error>(acc)
+        }).map(BytesMut::freeze)
+            .map(Bytes::from)
+    });
+
+    Box::new(fut)
+}
+
+/// Splits `[0, content_length)` into consecutive, inclusive `(start, end)`
+/// byte ranges of at most `chunk_size` bytes each. `chunk_size` must be at
+/// least 1; callers are expected to clamp it (see `chunked_range_stream`).
+fn chunk_ranges(content_length: u64, chunk_size: u64) -> Vec<(u64, u64)> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+
+    while start < content_length {
+        let end = (start + chunk_size - 1).min(content_length - 1);
+        ranges.push((start, end));
+        start = end + 1;
+    }
+
+    ranges
+}
+
+/// Lists the keys and common prefixes directly under `prefix` (using `/` as
+/// the delimiter), following `NextContinuationToken` across as many
+/// `ListObjectsV2` calls as needed, and renders the result as an HTML
+/// directory listing.
+fn list_directory(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    prefix: String,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    use futures::future::Loop;
+    use s3::S3;
+
+    #[derive(Default)]
+    struct Listing {
+        prefixes: Vec<String>,
+        objects: Vec<s3::Object>,
+        continuation_token: Option<String>,
+    }
+
+    let render_prefix = prefix.clone();
+    let fut = future::loop_fn(Listing::default(), move |mut listing| {
+        let client = Arc::clone(&client);
+        let bucket = bucket.clone();
+        let prefix = prefix.clone();
+
+        client
+            .list_objects_v2(s3::ListObjectsV2Request {
+                bucket,
+                prefix: Some(prefix),
+                delimiter: Some("/".to_string()),
+                continuation_token: listing.continuation_token.take(),
+                ..Default::default()
+            })
+            .from_err()
+            .map(move |output| {
+                if let Some(common_prefixes) = output.common_prefixes {
+                    listing
+                        .prefixes
+                        .extend(common_prefixes.into_iter().filter_map(|p| p.prefix));
+                }
+                if let Some(contents) = output.contents {
+                    listing.objects.extend(contents);
+                }
+
+                if output.is_truncated == Some(true) && output.next_continuation_token.is_some() {
+                    listing.continuation_token = output.next_continuation_token;
+                    Loop::Continue(listing)
+                } else {
+                    Loop::Break(listing)
+                }
+            })
+    }).map(move |listing| render_index_page(&render_prefix, listing.prefixes, listing.objects));
+
+    Box::new(fut)
+}
+
+/// Renders an HTML page listing subdirectories (`prefixes`) and files
+/// (`objects`) found directly under `prefix`.
+fn render_index_page(
+    prefix: &str,
+    mut prefixes: Vec<String>,
+    mut objects: Vec<s3::Object>,
+) -> HttpResponse {
+    prefixes.sort();
+    objects.sort_by(|a, b| a.key.cmp(&b.key));
+
+    let mut body = String::new();
+    body.push_str("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\">");
+    body.push_str(&format!("<title>Index of /{}</title></head>\n<body>\n", escape_html(prefix)));
+    body.push_str(&format!("<h1>Index of /{}</h1>\n", escape_html(prefix)));
+    body.push_str("<table>\n<tr><th>Name</th><th>Size</th><th>Last Modified</th></tr>\n");
+
+    if !prefix.is_empty() {
+        body.push_str("<tr><td><a href=\"../\">..</a></td><td></td><td></td></tr>\n");
+    }
+
+    for dir_prefix in &prefixes {
+        let name = dir_prefix.strip_prefix(prefix).unwrap_or(dir_prefix.as_str()).to_string();
+        body.push_str(&format!(
+            "<tr><td><a href=\"/{href}\">{name}</a></td><td></td><td></td></tr>\n",
+            href = escape_html(dir_prefix),
+            name = escape_html(&name),
+        ));
+    }
+
+    for object in &objects {
+        let key = match object.key {
+            Some(ref key) => key,
+            None => continue,
+        };
+        let name = key.strip_prefix(prefix).unwrap_or(key.as_str()).to_string();
+        if name.is_empty() {
+            continue;
+        }
+
+        let size = object.size.map(format_size).unwrap_or_default();
+        let last_modified = object.last_modified.clone().unwrap_or_default();
+        body.push_str(&format!(
+            "<tr><td><a href=\"/{href}\">{name}</a></td><td>{size}</td><td>{last_modified}</td></tr>\n",
+            href = escape_html(key),
+            name = escape_html(&name),
+            size = size,
+            last_modified = escape_html(&last_modified),
+        ));
+    }
+
+    body.push_str("</table>\n</body>\n</html>\n");
+
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(body)
+}
+
+fn escape_html(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn format_size(bytes: i64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB", "TiB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", size, UNITS[unit])
+}
+
+/// Handles `PUT` requests by streaming the request body into S3, using a
+/// multipart upload for anything that doesn't fit in a single
+/// [`MULTIPART_PART_SIZE`] part. Parts are uploaded as they fill, so peak
+/// memory use is bounded by `MULTIPART_PART_SIZE`, not the object's size.
+fn put_handler(
+    (req, path): (HttpRequest<State>, UrlPath<String>),
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    let client = Arc::clone(&req.state().s3_client);
+    let bucket = req.state().config.bucket.clone();
+    let key = path.into_inner();
+
+    if key.is_empty() {
+        return Box::new(future::ok(HttpResponse::BadRequest().body("400 - Missing key")));
+    }
+
+    let content_type = req.headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .map(String::from);
+
+    let initial = UploadAccumulator {
+        client,
+        bucket,
+        key,
+        content_type,
+        current: Vec::new(),
+        multipart: None,
+    };
+
+    let resp = req.payload()
+        .map_err(Error::from)
+        .fold(initial, |acc, chunk| flush_chunk(acc, &chunk))
+        .and_then(finish_upload);
+
+    Box::new(resp)
+}
+
+/// Uploads a single buffer with a plain `PutObjectRequest`.
+fn put_object(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    content_type: Option<String>,
+    data: Vec<u8>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    use s3::S3;
+
+    debug!("Uploading {} bytes to {} via PutObject", data.len(), key);
+    let fut = client
+        .put_object(s3::PutObjectRequest {
+            bucket,
+            key,
+            content_type,
+            body: Some(data.into()),
+            ..Default::default()
+        })
+        .from_err()
+        .map(|res| {
+            let mut builder = HttpResponse::Ok();
+            if let Some(e_tag) = res.e_tag {
+                builder.header("ETag", e_tag);
+            }
+            builder.finish()
+        });
+
+    Box::new(fut)
+}
+
+/// Accumulates `put_handler`'s payload chunks into `MULTIPART_PART_SIZE`
+/// parts. `multipart` stays `None` for as long as the object could still
+/// turn out to fit in a single part; the first time `current` overflows,
+/// [`flush_chunk`] starts a multipart upload and moves every later part
+/// through it, instead of buffering the whole object up front.
+struct UploadAccumulator {
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    content_type: Option<String>,
+    current: Vec<u8>,
+    multipart: Option<MultipartState>,
+}
+
+/// The in-progress state of a multipart upload: the id S3 assigned it and
+/// the `{PartNumber, ETag}` pairs uploaded so far.
+struct MultipartState {
+    upload_id: String,
+    part_number: i64,
+    completed: Vec<s3::CompletedPart>,
+}
+
+/// Feeds one payload chunk into `acc.current`, uploading it as a part (first
+/// starting a multipart upload if one hasn't started yet) once it grows past
+/// `MULTIPART_PART_SIZE`.
+fn flush_chunk(
+    mut acc: UploadAccumulator,
+    chunk: &bytes::Bytes,
+) -> Box<Future<Item = UploadAccumulator, Error = Error>> {
+    acc.current.extend_from_slice(chunk);
+    if acc.current.len() <= MULTIPART_PART_SIZE {
+        return Box::new(future::ok(acc));
+    }
+
+    let part = std::mem::replace(&mut acc.current, Vec::new());
+    let client = Arc::clone(&acc.client);
+    let bucket = acc.bucket.clone();
+    let key = acc.key.clone();
+
+    let uploaded = match acc.multipart.take() {
+        Some(state) => upload_part(client, bucket, key, state, part),
+        None => start_multipart_upload(client, bucket, key, acc.content_type.clone(), part),
+    };
+
+    Box::new(uploaded.map(move |state| {
+        acc.multipart = Some(state);
+        acc
+    }))
+}
+
+/// Issues `CreateMultipartUpload`, then uploads `part` as its first part.
+fn start_multipart_upload(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    content_type: Option<String>,
+    part: Vec<u8>,
+) -> Box<Future<Item = MultipartState, Error = Error>> {
+    use s3::S3;
+
+    let fut = client
+        .create_multipart_upload(s3::CreateMultipartUploadRequest {
+            bucket: bucket.clone(),
+            key: key.clone(),
+            content_type,
+            ..Default::default()
+        })
+        .from_err()
+        .and_then(move |created| {
+            let upload_id = created
+                .upload_id
+                .expect("CreateMultipartUpload returned no upload id");
+            debug!("Started multipart upload {} for {}", upload_id, key);
+
+            let state = MultipartState {
+                upload_id,
+                part_number: 0,
+                completed: Vec::new(),
+            };
+            upload_part(client, bucket, key, state, part)
+        });
+
+    Box::new(fut)
+}
+
+/// Uploads `part` as the next part of an already-started multipart upload.
+fn upload_part(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    mut state: MultipartState,
+    part: Vec<u8>,
+) -> Box<Future<Item = MultipartState, Error = Error>> {
+    use s3::S3;
+
+    state.part_number += 1;
+    let part_number = state.part_number;
+    debug!(
+        "Uploading part {} of {} ({} bytes)",
+        part_number,
+        key,
+        part.len()
+    );
+
+    let fut = client
+        .upload_part(s3::UploadPartRequest {
+            bucket,
+            key,
+            upload_id: state.upload_id.clone(),
+            part_number,
+            body: Some(part.into()),
+            ..Default::default()
+        })
+        .from_err()
+        .map(move |res| {
+            state.completed.push(s3::CompletedPart {
+                e_tag: res.e_tag,
+                part_number: Some(part_number),
+            });
+            state
+        });
+
+    Box::new(fut)
+}
+
+/// Finishes the upload once the payload stream ends: a plain `PutObjectRequest`
+/// if the object never grew past one part, otherwise the trailing part (if
+/// any) followed by `CompleteMultipartUpload`, aborting the upload if either
+/// step fails so no orphaned parts are left behind on the bucket.
+fn finish_upload(acc: UploadAccumulator) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    match acc.multipart {
+        None => put_object(acc.client, acc.bucket, acc.key, acc.content_type, acc.current),
+        Some(state) => {
+            let UploadAccumulator {
+                client,
+                bucket,
+                key,
+                current: trailing,
+                ..
+            } = acc;
+
+            let complete_client = Arc::clone(&client);
+            let complete_bucket = bucket.clone();
+            let complete_key = key.clone();
+
+            let abort_client = Arc::clone(&client);
+            let abort_bucket = bucket.clone();
+            let abort_key = key.clone();
+            let abort_upload_id = state.upload_id.clone();
+
+            let trailing_part: Box<Future<Item = MultipartState, Error = Error>> =
+                if trailing.is_empty() {
+                    Box::new(future::ok(state))
+                } else {
+                    upload_part(Arc::clone(&client), bucket.clone(), key.clone(), state, trailing)
+                };
+
+            let fut = trailing_part
+                .and_then(move |state| {
+                    complete_multipart_upload(
+                        complete_client,
+                        complete_bucket,
+                        complete_key,
+                        state.upload_id,
+                        state.completed,
+                    )
+                })
+                .then(move |result| match result {
+                    Ok(resp) => Either::A(future::ok(resp)),
+                    Err(e) => Either::B(
+                        abort_multipart_upload(
+                            abort_client,
+                            abort_bucket,
+                            abort_key,
+                            abort_upload_id,
+                        ).then(move |_| Err(e)),
+                    ),
+                });
+
+            Box::new(fut)
+        }
+    }
+}
+
+fn complete_multipart_upload(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    upload_id: String,
+    parts: Vec<s3::CompletedPart>,
+) -> Box<Future<Item = HttpResponse, Error = Error>> {
+    use s3::S3;
+
+    let fut = client
+        .complete_multipart_upload(s3::CompleteMultipartUploadRequest {
+            bucket,
+            key,
+            upload_id,
+            multipart_upload: Some(s3::CompletedMultipartUpload { parts: Some(parts) }),
+            ..Default::default()
+        })
+        .from_err()
+        .map(|res| {
+            let mut builder = HttpResponse::Ok();
+            if let Some(e_tag) = res.e_tag {
+                builder.header("ETag", e_tag);
+            }
+            builder.finish()
+        });
+
+    Box::new(fut)
+}
+
+fn abort_multipart_upload(
+    client: Arc<s3::S3Client>,
+    bucket: String,
+    key: String,
+    upload_id: String,
+) -> Box<Future<Item = (), Error = Error>> {
+    use s3::S3;
+
+    warn!("Aborting multipart upload {} for key {}", upload_id, key);
+    let fut = client
+        .abort_multipart_upload(s3::AbortMultipartUploadRequest {
+            bucket,
+            key,
+            upload_id,
+            ..Default::default()
+        })
+        .then(|result| {
+            if let Err(e) = result {
+                error!("Failed to abort multipart upload: {}", e);
+            }
+            Ok(())
+        });
+
+    Box::new(fut)
+}
+
+/// Injects `Access-Control-Allow-Origin` into every response whose request
+/// carries an `Origin` header matched by `[cors]`. Preflight `OPTIONS`
+/// requests are handled separately by [`preflight_handler`], since they
+/// must short-circuit before ever reaching S3.
+struct Cors;
+
+impl middleware::Middleware<State> for Cors {
+    fn response(
+        &self,
+        req: &HttpRequest<State>,
+        mut resp: HttpResponse,
+    ) -> actix_web::Result<middleware::Response> {
+        let config = &req.state().config;
+        if let Some(ref cors) = config.cors {
+            // The response varies by `Origin` regardless of whether this
+            // particular origin matched, so a cache must not reuse a
+            // response (with or without ACAO) for a different origin.
+            resp.headers_mut()
+                .insert(header::VARY, header::HeaderValue::from_static("Origin"));
+
+            let allowed_origin = req.headers()
+                .get("Origin")
+                .and_then(|v| v.to_str().ok())
+                .and_then(|origin| matched_origin(cors, origin))
+                .and_then(|origin| header::HeaderValue::from_str(origin).ok());
+
+            if let Some(value) = allowed_origin {
+                resp.headers_mut()
+                    .insert(header::ACCESS_CONTROL_ALLOW_ORIGIN, value);
+            }
+        }
+
+        Ok(middleware::Response::Done(resp))
+    }
+}
+
+/// Answers CORS preflight requests directly, without involving S3.
+fn preflight_handler(req: HttpRequest<State>) -> HttpResponse {
+    let config = &req.state().config;
+    let cors = match config.cors {
+        Some(ref cors) => cors,
+        None => return HttpResponse::NotFound().body("404 - Not found"),
+    };
+
+    let origin = req.headers()
+        .get("Origin")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|origin| matched_origin(cors, origin));
+
+    let mut builder = HttpResponse::NoContent();
+    builder.header(header::VARY, "Origin");
+    if let Some(origin) = origin {
+        builder.header(header::ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+        builder.header(
+            header::ACCESS_CONTROL_ALLOW_METHODS,
+            cors.allowed_methods.join(", "),
+        );
+        if !cors.allowed_headers.is_empty() {
+            builder.header(
+                header::ACCESS_CONTROL_ALLOW_HEADERS,
+                cors.allowed_headers.join(", "),
+            );
+        }
+        builder.header(header::ACCESS_CONTROL_MAX_AGE, cors.max_age.to_string());
+    }
+
+    builder.finish()
+}
+
 fn run() -> Result<()> {
-    use actix_web::middleware;
     use std::env;
 
     configure_logger();
@@ -205,8 +1287,7 @@ fn run() -> Result<()> {
 
     info!("Hosting content from bucket '{}' ", config.bucket);
 
-    let region = config.region.parse()?;
-    let s3_client = Arc::new(s3::S3Client::new(region));
+    let s3_client = Arc::new(build_s3_client(&config)?);
     let workers = config.workers;
     let addr = format!("{}:{}", config.host, config.port);
 
@@ -215,8 +1296,11 @@ fn run() -> Result<()> {
             s3_client: Arc::clone(&s3_client),
             config: config.clone(),
         }).middleware(middleware::Logger::new(r#"%t "%r" %s %b %T"#))
+            .middleware(Cors)
             .route("/{path:.*}", Method::GET, handler)
             .route("/{path:.*}", Method::HEAD, handler)
+            .route("/{path:.*}", Method::PUT, put_handler)
+            .route("/{path:.*}", Method::OPTIONS, preflight_handler)
     }).workers(workers.unwrap_or_else(|| num_cpus::get()))
         .bind(addr)?
         .run();